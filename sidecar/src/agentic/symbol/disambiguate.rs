@@ -0,0 +1,98 @@
+//! Picks the best candidate when a symbol name resolves to more than one
+//! definition or outline node.
+//!
+//! Several code paths used to paper over this with `definitions().remove(0)`
+//! / `outline_nodes.remove(0)`, silently grabbing whatever the tool returned
+//! first. `DefinitionDisambiguator` scores every candidate on cheap,
+//! deterministic signals instead, so an exact/same-file/top-level match wins
+//! over an arbitrary one.
+
+/// The signals we can cheaply read off a candidate definition/outline node,
+/// regardless of which concrete tool response type it came from.
+#[derive(Debug, Clone, Copy)]
+pub struct CandidateSignals<'a> {
+    pub name: &'a str,
+    pub file_path: &'a str,
+    /// true when the candidate is nested inside another symbol (e.g. a
+    /// method on a class) rather than a top-level definition.
+    pub is_member: bool,
+}
+
+pub struct DefinitionDisambiguator;
+
+impl DefinitionDisambiguator {
+    /// Returns the index of the best-scoring candidate; the earliest
+    /// candidate wins a tie.
+    pub fn rank(
+        symbol_name: &str,
+        requesting_file_path: Option<&str>,
+        candidates: &[CandidateSignals],
+    ) -> Option<usize> {
+        candidates
+            .iter()
+            .enumerate()
+            .map(|(index, candidate)| (index, score(symbol_name, requesting_file_path, candidate)))
+            .fold(None, |best, (index, score)| match best {
+                Some((_, best_score)) if best_score >= score => best,
+                _ => Some((index, score)),
+            })
+            .map(|(index, _)| index)
+    }
+}
+
+const EXACT_NAME_SCORE: i32 = 100;
+const SAME_FILE_SCORE: i32 = 10;
+const TOP_LEVEL_SCORE: i32 = 1;
+
+fn score(symbol_name: &str, requesting_file_path: Option<&str>, candidate: &CandidateSignals) -> i32 {
+    let mut score = 0;
+    if candidate.name == symbol_name {
+        score += EXACT_NAME_SCORE;
+    }
+    if requesting_file_path == Some(candidate.file_path) {
+        score += SAME_FILE_SCORE;
+    }
+    if !candidate.is_member {
+        score += TOP_LEVEL_SCORE;
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate<'a>(file_path: &'a str, is_member: bool) -> CandidateSignals<'a> {
+        CandidateSignals {
+            name: "foo",
+            file_path,
+            is_member,
+        }
+    }
+
+    #[test]
+    fn no_candidates_returns_none() {
+        assert!(DefinitionDisambiguator::rank("foo", None, &[]).is_none());
+    }
+
+    #[test]
+    fn same_file_candidate_wins_over_an_arbitrary_other() {
+        let candidates = [candidate("other.rs", false), candidate("here.rs", false)];
+        let best_index = DefinitionDisambiguator::rank("foo", Some("here.rs"), &candidates).unwrap();
+        assert_eq!(best_index, 1);
+    }
+
+    #[test]
+    fn top_level_candidate_wins_over_a_member() {
+        let candidates = [candidate("a.rs", true), candidate("b.rs", false)];
+        let best_index = DefinitionDisambiguator::rank("foo", None, &candidates).unwrap();
+        assert_eq!(best_index, 1);
+    }
+
+    #[test]
+    fn first_candidate_wins_ties() {
+        let candidates = [candidate("a.rs", false), candidate("b.rs", false)];
+        let best_index = DefinitionDisambiguator::rank("foo", None, &candidates).unwrap();
+        assert_eq!(best_index, 0);
+    }
+}
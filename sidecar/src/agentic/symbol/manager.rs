@@ -25,7 +25,11 @@ use crate::{
     inline_completion::symbols_tracker::SymbolTrackerInline,
 };
 
+use super::disambiguate::{CandidateSignals, DefinitionDisambiguator};
 use super::identifier::{MechaCodeSymbolThinking, Snippet};
+use super::resolution_cache::{ResolutionCacheCounters, SymbolResolutionCache};
+use super::symbol_index::{FuzzySymbolMatch, SymbolIndex};
+use super::visibility::{SymbolVisibilityClassifier, VisibilitySignals};
 use super::{
     errors::SymbolError,
     events::input::SymbolInputEvent,
@@ -47,36 +51,127 @@ pub struct SymbolManager {
     tools: Arc<ToolBroker>,
     symbol_broker: Arc<SymbolTrackerInline>,
     editor_url: String,
+    // workspace-wide fuzzy name -> location index, kept up to date as we
+    // open and parse files for the symbols above
+    symbol_index: Arc<SymbolIndex>,
+    // memoizes file opens, outlines, and resolved snippets so revisiting the
+    // same file/symbol while walking a dense symbol graph doesn't re-invoke
+    // tools; see its hit/miss counters when tuning `max_concurrent_requests`
+    // in `with_concurrency` below
+    resolution_cache: Arc<SymbolResolutionCache>,
 }
 
+// how many SymbolEventRequests SymbolManager will drive at once when the
+// caller doesn't pick a number explicitly via `with_concurrency`. Kept at 1
+// (i.e. fully serial) since `SymbolLocker::process_request` doesn't itself
+// serialize same-symbol-id requests - see `with_concurrency`'s doc comment.
+const DEFAULT_MAX_CONCURRENT_SYMBOL_REQUESTS: usize = 1;
+
+// `drive_requests` warns whenever the unbounded channel's backlog exceeds this
+// many times `max_in_flight`, since that many events waiting behind a full set of
+// permits is a sign producers are outrunning processing rather than a normal
+// burst
+const BACKLOG_WARN_MULTIPLIER: usize = 4;
+
 impl SymbolManager {
     pub fn new(
         tools: Arc<ToolBroker>,
         symbol_broker: Arc<SymbolTrackerInline>,
         editor_url: String,
     ) -> Self {
-        let (sender, mut receier) = tokio::sync::mpsc::unbounded_channel::<(
+        Self::with_concurrency(
+            tools,
+            symbol_broker,
+            editor_url,
+            DEFAULT_MAX_CONCURRENT_SYMBOL_REQUESTS,
+        )
+    }
+
+    /// Same as `Self::new`, but lets the caller pick how many
+    /// `SymbolEventRequest`s get driven concurrently. Raising this past 1
+    /// lets two requests for the same symbol id run at once; nothing in this
+    /// crate serializes per symbol id to prevent that, so don't raise it
+    /// until that's added (either here or confirmed inside `SymbolLocker`).
+    pub fn with_concurrency(
+        tools: Arc<ToolBroker>,
+        symbol_broker: Arc<SymbolTrackerInline>,
+        editor_url: String,
+        max_concurrent_requests: usize,
+    ) -> Self {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel::<(
             SymbolEventRequest,
             tokio::sync::oneshot::Sender<SymbolEventResponse>,
         )>();
         let symbol_locker = SymbolLocker::new(sender.clone(), tools.clone());
         let cloned_symbol_locker = symbol_locker.clone();
-        tokio::spawn(async move {
-            // TODO(skcd): Make this run in full parallelism in the future, for
-            // now this is fine
-            while let Some(event) = receier.recv().await {
-                let _ = cloned_symbol_locker.process_request(event).await;
-            }
-        });
+        tokio::spawn(Self::drive_requests(
+            receiver,
+            cloned_symbol_locker,
+            max_concurrent_requests,
+        ));
         Self {
             sender,
             symbol_locker,
             tools,
             symbol_broker,
             editor_url,
+            symbol_index: Arc::new(SymbolIndex::new()),
+            resolution_cache: Arc::new(SymbolResolutionCache::new()),
+        }
+    }
+
+    /// Pulls `SymbolEventRequest`s off `receiver` and runs up to
+    /// `max_concurrent_requests` of them at once. A `Semaphore` permit is
+    /// acquired *before* pulling the next event off the channel, so no more
+    /// than `max_concurrent_requests` tasks run at once - that bounds
+    /// concurrent processing, not how large the (unbounded) channel's
+    /// backlog can grow while every permit is held, so this also logs each
+    /// time it pulls an event while the backlog is over a threshold.
+    async fn drive_requests(
+        mut receiver: tokio::sync::mpsc::UnboundedReceiver<(
+            SymbolEventRequest,
+            tokio::sync::oneshot::Sender<SymbolEventResponse>,
+        )>,
+        symbol_locker: SymbolLocker,
+        max_concurrent_requests: usize,
+    ) {
+        let max_in_flight = max_concurrent_requests.max(1);
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(max_in_flight));
+        loop {
+            let Ok(permit) = semaphore.clone().acquire_owned().await else {
+                break;
+            };
+            let Some(event) = receiver.recv().await else {
+                break;
+            };
+            let backlog = receiver.len();
+            if backlog > max_in_flight * BACKLOG_WARN_MULTIPLIER {
+                println!(
+                    "symbol manager: unbounded request channel backlog is {} (max in flight is {})",
+                    backlog, max_in_flight
+                );
+            }
+            let symbol_locker = symbol_locker.clone();
+            tokio::spawn(async move {
+                let _ = symbol_locker.process_request(event).await;
+                drop(permit);
+            });
         }
     }
 
+    /// Looks up symbols whose name is within a small edit distance of
+    /// `query`, for when the agent only knows a symbol approximately.
+    pub async fn fuzzy_find_symbol(&self, query: &str) -> Vec<FuzzySymbolMatch> {
+        self.symbol_index.fuzzy_find(query, 2).await
+    }
+
+    /// Hit/miss counts for the file/outline/snippet/find-in-file resolution
+    /// caches, meant to be watched while tuning `max_concurrent_requests` in
+    /// `Self::with_concurrency`.
+    pub fn resolution_cache_counters(&self) -> ResolutionCacheCounters {
+        self.resolution_cache.counters()
+    }
+
     // once we have the initial request, which we will go through the initial request
     // mode once, we have the symbols from it we can use them to spin up sub-symbols as well
     pub async fn initial_request(&self, input_event: SymbolInputEvent) -> Result<(), SymbolError> {
@@ -123,7 +218,11 @@ impl SymbolManager {
         // first occurance of the symbol and grab the location
         let file_content = self.file_open(snippet.file_path().to_owned()).await?;
         let find_in_file = self
-            .find_in_file(file_content.contents(), symbol_name.to_owned())
+            .find_in_file(
+                snippet.file_path(),
+                file_content.contents(),
+                symbol_name.to_owned(),
+            )
             .await?;
         if let Some(position) = find_in_file.get_position() {
             self.tools
@@ -147,30 +246,44 @@ impl SymbolManager {
 
     async fn find_in_file(
         &self,
+        fs_file_path: &str,
         file_content: String,
         symbol: String,
     ) -> Result<FindInFileResponse, SymbolError> {
-        self.tools
-            .invoke(ToolInput::GrepSingleFile(FindInFileRequest::new(
-                file_content,
-                symbol,
-            )))
+        let tools = self.tools.clone();
+        let request_symbol = symbol.clone();
+        self.resolution_cache
+            .get_or_find_in_file(fs_file_path, &symbol, || async move {
+                tools
+                    .invoke(ToolInput::GrepSingleFile(FindInFileRequest::new(
+                        file_content,
+                        request_symbol,
+                    )))
+                    .await
+                    .map_err(|e| SymbolError::ToolError(e))?
+                    .grep_single_file()
+                    .ok_or(SymbolError::WrongToolOutput)
+            })
             .await
-            .map_err(|e| SymbolError::ToolError(e))?
-            .grep_single_file()
-            .ok_or(SymbolError::WrongToolOutput)
     }
 
     async fn file_open(&self, fs_file_path: String) -> Result<OpenFileResponse, SymbolError> {
-        self.tools
-            .invoke(ToolInput::OpenFile(OpenFileRequest::new(
-                fs_file_path,
-                self.editor_url.to_owned(),
-            )))
+        let tools = self.tools.clone();
+        let editor_url = self.editor_url.clone();
+        let request_file_path = fs_file_path.clone();
+        self.resolution_cache
+            .get_or_open_file(&fs_file_path, || async move {
+                tools
+                    .invoke(ToolInput::OpenFile(OpenFileRequest::new(
+                        request_file_path,
+                        editor_url,
+                    )))
+                    .await
+                    .map_err(|e| SymbolError::ToolError(e))?
+                    .get_file_open_response()
+                    .ok_or(SymbolError::WrongToolOutput)
+            })
             .await
-            .map_err(|e| SymbolError::ToolError(e))?
-            .get_file_open_response()
-            .ok_or(SymbolError::WrongToolOutput)
     }
 
     async fn go_to_definition(
@@ -190,60 +303,107 @@ impl SymbolManager {
             .ok_or(SymbolError::WrongToolOutput)
     }
 
-    /// Grabs the symbol content and the range in the file which it is present in
+    /// Grabs the symbol content and the range in the file which it is present in.
+    /// `requesting_file_path` is the file the lookup that produced `definition`
+    /// originated from, so `DefinitionDisambiguator` can actually score a
+    /// same-file candidate above one living elsewhere instead of always
+    /// comparing against `None`.
     async fn grab_symbol_content_from_definition(
         &self,
         symbol_name: &str,
         definition: GoToDefinitionResponse,
+        requesting_file_path: Option<&str>,
     ) -> Result<Snippet, SymbolError> {
         // here we first try to open the file
         // and then read the symbols from it nad then parse
         // it out properly
         // since its very much possible that we get multiple definitions over here
-        // we have to figure out how to pick the best one over here
-        // TODO(skcd): This will break if we are unable to get definitions properly
-        let definition = definition.definitions().remove(0);
-        let _ = self.file_open(definition.file_path().to_owned()).await?;
-        // grab the symbols from the file
-        // but we can also try getting it from the symbol broker
-        // because we are going to open a file and send a signal to the signal broker
-        // let symbols = self
-        //     .editor_parsing
-        //     .for_file_path(definition.file_path())
-        //     .ok_or(ToolError::NotSupportedLanguage)?
-        //     .generate_file_outline_str(file_content.contents().as_bytes());
-        let symbols = self
-            .symbol_broker
-            .get_symbols_outline(definition.file_path())
-            .await;
-        if let Some(symbols) = symbols {
-            let symbols = self.grab_symbols_from_outline(symbols, symbol_name);
-            // find the first symbol and grab back its content
-            symbols
-                .iter()
-                .find(|symbol| symbol.name() == symbol_name)
-                .map(|symbol| {
-                    Snippet::new(
-                        symbol.name().to_owned(),
-                        symbol.range().clone(),
-                        definition.file_path().to_owned(),
-                    )
-                })
-                .ok_or(SymbolError::ToolError(ToolError::SymbolNotFound(
-                    symbol_name.to_owned(),
-                )))
-        } else {
-            Err(SymbolError::ToolError(ToolError::SymbolNotFound(
-                symbol_name.to_owned(),
-            )))
-        }
+        // we score every candidate and take the best one instead of
+        // arbitrarily grabbing the first
+        let definitions = definition.definitions();
+        let candidates: Vec<CandidateSignals> = definitions
+            .iter()
+            .map(|definition| CandidateSignals {
+                name: symbol_name,
+                file_path: definition.file_path(),
+                is_member: false,
+            })
+            .collect();
+        let best_index =
+            DefinitionDisambiguator::rank(symbol_name, requesting_file_path, &candidates).unwrap_or(0);
+        let definition = definition.definitions().remove(best_index);
+        let fs_file_path = definition.file_path().to_owned();
+        self.resolution_cache
+            .get_or_resolve_snippet(&fs_file_path, symbol_name, || async {
+                let _ = self.file_open(fs_file_path.clone()).await?;
+                // grab the symbols from the file
+                // but we can also try getting it from the symbol broker
+                // because we are going to open a file and send a signal to the signal broker
+                // let symbols = self
+                //     .editor_parsing
+                //     .for_file_path(definition.file_path())
+                //     .ok_or(ToolError::NotSupportedLanguage)?
+                //     .generate_file_outline_str(file_content.contents().as_bytes());
+                let symbol_broker = self.symbol_broker.clone();
+                let outline_file_path = fs_file_path.clone();
+                let symbols = self
+                    .resolution_cache
+                    .get_or_parse_outline(&fs_file_path, || async move {
+                        symbol_broker.get_symbols_outline(&outline_file_path).await
+                    })
+                    .await;
+                if let Some(symbols) = symbols {
+                    self.symbol_index.index_file(&fs_file_path, &symbols).await;
+                    let symbols = self.grab_symbols_from_outline(symbols, symbol_name);
+                    let candidates: Vec<CandidateSignals> = symbols
+                        .iter()
+                        .map(|(symbol, is_member)| CandidateSignals {
+                            name: symbol.name(),
+                            file_path: &fs_file_path,
+                            is_member: *is_member,
+                        })
+                        .collect();
+                    let best_index =
+                        match DefinitionDisambiguator::rank(symbol_name, Some(&fs_file_path), &candidates) {
+                            Some(best_index) => best_index,
+                            None => {
+                                return Err(SymbolError::ToolError(ToolError::SymbolNotFound(
+                                    symbol_name.to_owned(),
+                                )))
+                            }
+                        };
+                    symbols
+                        .get(best_index)
+                        .map(|(symbol, _)| {
+                            Snippet::new(
+                                symbol.name().to_owned(),
+                                symbol.range().clone(),
+                                fs_file_path.clone(),
+                            )
+                        })
+                        .ok_or(SymbolError::ToolError(ToolError::SymbolNotFound(
+                            symbol_name.to_owned(),
+                        )))
+                } else {
+                    Err(SymbolError::ToolError(ToolError::SymbolNotFound(
+                        symbol_name.to_owned(),
+                    )))
+                }
+            })
+            .await
     }
 
+    /// Returns every outline node matching `symbol_name`, tagged with
+    /// whether it is a member (nested inside another symbol) so callers can
+    /// feed it to `DefinitionDisambiguator` when there's more than one.
+    /// Synthetic/compiler-generated names (e.g. `..`-prefixed rest-pattern
+    /// labels) never come back from here, regardless of whether they match
+    /// `symbol_name` - they aren't symbols worth resolving a snippet for.
     fn grab_symbols_from_outline(
         &self,
         outline_nodes: Vec<OutlineNode>,
         symbol_name: &str,
-    ) -> Vec<OutlineNodeContent> {
+    ) -> Vec<(OutlineNodeContent, bool)> {
         outline_nodes
             .into_iter()
             .filter_map(|node| {
@@ -252,13 +412,13 @@ impl SymbolManager {
                     // or a function inside it so we can check for it
                     // properly here
                     if node.content().name() == symbol_name {
-                        Some(vec![node.content().clone()])
+                        Some(vec![(node.content().clone(), false)])
                     } else {
                         Some(
                             node.children()
                                 .into_iter()
                                 .filter(|node| node.name() == symbol_name)
-                                .map(|node| node.clone())
+                                .map(|node| (node.clone(), true))
                                 .collect::<Vec<_>>(),
                         )
                     }
@@ -266,16 +426,27 @@ impl SymbolManager {
                     // we can just compare the node directly
                     // without looking at the children at this stage
                     if node.content().name() == symbol_name {
-                        Some(vec![node.content().clone()])
+                        Some(vec![(node.content().clone(), false)])
                     } else {
                         None
                     }
                 }
             })
             .flatten()
+            .filter(|(content, _)| !Self::is_synthetic(content.name()))
             .collect::<Vec<_>>()
     }
 
+    /// `true` when `SymbolVisibilityClassifier` flags `name` as a
+    /// synthetic/generated label rather than a real symbol.
+    fn is_synthetic(name: &str) -> bool {
+        SymbolVisibilityClassifier::classify(&VisibilitySignals {
+            name,
+            is_member: false,
+        })
+        .should_skip()
+    }
+
     // TODO(skcd): Improve this since we have code symbols which might be duplicated
     // because there can be repetitions and we can'nt be sure where they exist
     // one key hack here is that we can legit search for this symbol and get
@@ -348,11 +519,21 @@ impl SymbolManager {
                     language,
                 )
                 .await;
+            // the document we just added may have changed since the last
+            // time we cached an outline/snippet for it, so drop anything
+            // stale before we ask for the outline below
+            self.resolution_cache
+                .invalidate(file_open_result.fs_file_path(), file_open_result.contents())
+                .await;
 
             // we grab the outlines over here
+            let symbol_broker = self.symbol_broker.clone();
+            let outline_file_path = code_snippet.fs_file_path().to_owned();
             let outline_nodes = self
-                .symbol_broker
-                .get_symbols_outline(code_snippet.fs_file_path())
+                .resolution_cache
+                .get_or_parse_outline(code_snippet.fs_file_path(), || async move {
+                    symbol_broker.get_symbols_outline(&outline_file_path).await
+                })
                 .await;
 
             // We will either get an outline node or we will get None
@@ -360,6 +541,9 @@ impl SymbolManager {
             // - if the document has already been open, then its good
             // - otherwise we open the document and parse it again
             if let Some(outline_nodes) = outline_nodes {
+                self.symbol_index
+                    .index_file(code_snippet.fs_file_path(), &outline_nodes)
+                    .await;
                 let mut outline_nodes =
                     self.grab_symbols_from_outline(outline_nodes, code_snippet.symbol_name());
 
@@ -378,7 +562,11 @@ impl SymbolManager {
                     let file_content = file_data.contents();
                     // now we parse it and grab the outline nodes
                     let find_in_file = self
-                        .find_in_file(file_content, code_snippet.symbol_name().to_owned())
+                        .find_in_file(
+                            code_snippet.fs_file_path(),
+                            file_content,
+                            code_snippet.symbol_name().to_owned(),
+                        )
                         .await
                         .map(|find_in_file| find_in_file.get_position())
                         .ok()
@@ -393,16 +581,46 @@ impl SymbolManager {
                             .grab_symbol_content_from_definition(
                                 &code_snippet.symbol_name(),
                                 definition,
+                                Some(code_snippet.fs_file_path()),
                             )
                             .await?;
                         code_snippet.set_snippet(snippet_node);
+                    } else if let Some(fuzzy_match) = self
+                        .fuzzy_find_symbol(code_snippet.symbol_name())
+                        .await
+                        .into_iter()
+                        .next()
+                    {
+                        // the symbol's name doesn't literally appear in this
+                        // file's text - the agent may only know it
+                        // approximately, or it actually lives elsewhere in
+                        // the workspace - so fall back to the fuzzy
+                        // workspace-wide index instead of leaving this
+                        // symbol unresolved
+                        code_snippet.set_snippet(Snippet::new(
+                            fuzzy_match.entry.name,
+                            fuzzy_match.entry.range,
+                            fuzzy_match.entry.file_path,
+                        ));
                     }
                 } else {
-                    // if we have multiple outline nodes, then we need to select
-                    // the best one, this will require another invocation from the LLM
-                    // we have the symbol, we can just use the outline nodes which is
-                    // the first
-                    let outline_node = outline_nodes.remove(0);
+                    // if we have multiple outline nodes, score them instead
+                    // of arbitrarily taking the first one
+                    let candidates: Vec<CandidateSignals> = outline_nodes
+                        .iter()
+                        .map(|(node, is_member)| CandidateSignals {
+                            name: node.name(),
+                            file_path: node.fs_file_path(),
+                            is_member: *is_member,
+                        })
+                        .collect();
+                    let best_index = DefinitionDisambiguator::rank(
+                        code_snippet.symbol_name(),
+                        Some(code_snippet.fs_file_path()),
+                        &candidates,
+                    )
+                    .unwrap_or(0);
+                    let (outline_node, _) = outline_nodes.remove(best_index);
                     code_snippet.set_snippet(Snippet::new(
                         outline_node.name().to_owned(),
                         outline_node.range().clone(),
@@ -0,0 +1,308 @@
+//! Fuzzy, workspace-wide symbol name index.
+//!
+//! `SymbolManager` used to resolve every symbol by opening a file and doing a
+//! linear `find_in_file`/`grab_symbols_from_outline` scan, and
+//! `important_symbols` repeats this per snippet even though most of the
+//! symbols it visits have already been parsed once. `SymbolIndex` keeps a
+//! fuzzy name -> location index over every outline node the symbol broker has
+//! parsed so far, backed by an `fst` finite-state transducer: querying walks
+//! a Levenshtein automaton against the transducer in lock-step instead of
+//! re-scanning files.
+//!
+//! `fst::automaton::Levenshtein` requires the crate's `levenshtein` Cargo
+//! feature (it pulls in `fst-levenshtein`); the manifest needs
+//! `fst = { version = "...", features = ["levenshtein"] }`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use fst::automaton::Levenshtein;
+use fst::{IntoStreamer, Map as FstMap, MapBuilder, Streamer};
+use tokio::sync::RwLock;
+
+use crate::chunking::text_document::Range;
+use crate::chunking::types::{OutlineNode, OutlineNodeContent};
+
+use super::visibility::{SymbolVisibilityClassifier, VisibilitySignals};
+
+/// Location + shape of a single indexed symbol.
+#[derive(Debug, Clone)]
+pub struct SymbolIndexEntry {
+    pub name: String,
+    pub file_path: String,
+    pub range: Range,
+    /// true when this symbol is nested inside another (e.g. a method on a
+    /// class), as opposed to a top-level definition.
+    pub is_member: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct FuzzySymbolMatch {
+    pub entry: SymbolIndexEntry,
+    pub edit_distance: u32,
+}
+
+struct BuiltIndex {
+    map: FstMap<Vec<u8>>,
+    // one group of entries per fst value (several symbols can share a name
+    // across files, or even within one file)
+    entries: Vec<Vec<SymbolIndexEntry>>,
+    // the state generation this snapshot was built from; lets a rebuild that
+    // starts after a newer one already landed recognize it's obsolete and
+    // skip clobbering it
+    generation: u64,
+}
+
+#[derive(Default)]
+struct SymbolIndexState {
+    // per-file outline snapshot, so a single file's re-parse only needs to
+    // redo that file's contribution instead of the whole workspace
+    file_outlines: HashMap<String, Vec<SymbolIndexEntry>>,
+    // files touched by `index_file` since the last rebuild, cleared when a
+    // rebuild lands; nothing reads it, since every rebuild redoes the whole
+    // map from `file_outlines` while holding the write lock
+    dirty_files: HashSet<String>,
+    // bumped on every `index_file` call; `BuiltIndex::generation` records
+    // which of these a given snapshot reflects
+    generation: u64,
+    built: Option<Arc<BuiltIndex>>,
+}
+
+pub struct SymbolIndex {
+    state: Arc<RwLock<SymbolIndexState>>,
+}
+
+impl SymbolIndex {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(RwLock::new(SymbolIndexState::default())),
+        }
+    }
+
+    /// Registers (or re-registers) `fs_file_path`'s outline nodes with the
+    /// index and kicks off a rebuild in the background, so the (possibly
+    /// expensive, whole-workspace) fst rebuild never blocks the caller that
+    /// just finished parsing a file. Queries that take the read lock before
+    /// the rebuild takes the write lock answer against the last-built
+    /// snapshot; a query that arrives while the rebuild holds the write lock
+    /// waits for it and then answers against the new snapshot.
+    pub async fn index_file(&self, fs_file_path: &str, outline_nodes: &[OutlineNode]) {
+        let entries = flatten_outline(fs_file_path, outline_nodes);
+        let generation = {
+            let mut state = self.state.write().await;
+            state.file_outlines.insert(fs_file_path.to_owned(), entries);
+            state.dirty_files.insert(fs_file_path.to_owned());
+            state.generation += 1;
+            state.generation
+        };
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            Self::rebuild_at_least(&state, generation).await;
+        });
+    }
+
+    /// Fuzzy-matches `query` against every indexed name within
+    /// `max_edit_distance`, ranked by edit distance then by exact-prefix
+    /// match. Answers against whatever snapshot is currently built; if
+    /// nothing has been built yet (first query racing the very first
+    /// `index_file`) this rebuilds inline rather than returning empty.
+    pub async fn fuzzy_find(&self, query: &str, max_edit_distance: u32) -> Vec<FuzzySymbolMatch> {
+        let built = {
+            let state = self.state.read().await;
+            state.built.clone()
+        };
+        let built = match built {
+            Some(built) => built,
+            None => {
+                let generation = self.state.read().await.generation;
+                match Self::rebuild_at_least(&self.state, generation).await {
+                    Some(built) => built,
+                    None => return vec![],
+                }
+            }
+        };
+        Self::search(&built, query, max_edit_distance)
+    }
+
+    fn search(built: &BuiltIndex, query: &str, max_edit_distance: u32) -> Vec<FuzzySymbolMatch> {
+        let Ok(automaton) = Levenshtein::new(query, max_edit_distance) else {
+            return vec![];
+        };
+        let mut stream = built.map.search(automaton).into_stream();
+        let mut matches = vec![];
+        while let Some((name_bytes, id)) = stream.next() {
+            let name = String::from_utf8_lossy(name_bytes).into_owned();
+            let edit_distance = levenshtein_distance(query, &name);
+            if let Some(group) = built.entries.get(id as usize) {
+                for entry in group {
+                    matches.push(FuzzySymbolMatch {
+                        entry: entry.clone(),
+                        edit_distance,
+                    });
+                }
+            }
+        }
+        matches.sort_by(|a, b| {
+            a.edit_distance.cmp(&b.edit_distance).then_with(|| {
+                let a_prefix = a.entry.name.starts_with(query);
+                let b_prefix = b.entry.name.starts_with(query);
+                b_prefix.cmp(&a_prefix)
+            })
+        });
+        matches
+    }
+
+    /// Rebuilds the transducer from whatever `file_outlines` looks like
+    /// right now, unless a snapshot that already covers `generation` beat us
+    /// to it - so a burst of `index_file` calls only pays for one rebuild
+    /// for the generation they collectively produced, not one per call.
+    async fn rebuild_at_least(
+        state: &Arc<RwLock<SymbolIndexState>>,
+        generation: u64,
+    ) -> Option<Arc<BuiltIndex>> {
+        let mut state = state.write().await;
+        if let Some(built) = state.built.as_ref() {
+            if built.generation >= generation {
+                return Some(built.clone());
+            }
+        }
+        let mut grouped: BTreeMap<String, Vec<SymbolIndexEntry>> = BTreeMap::new();
+        for entries in state.file_outlines.values() {
+            for entry in entries {
+                grouped
+                    .entry(entry.name.clone())
+                    .or_default()
+                    .push(entry.clone());
+            }
+        }
+        let mut builder = MapBuilder::memory();
+        let mut entries = Vec::with_capacity(grouped.len());
+        for (name, group) in grouped.into_iter() {
+            // names are visited in sorted order because `grouped` is a
+            // BTreeMap, which is what MapBuilder::insert requires
+            if builder.insert(name.as_bytes(), entries.len() as u64).is_ok() {
+                entries.push(group);
+            }
+        }
+        let current_generation = state.generation;
+        if let Some(map) = builder
+            .into_inner()
+            .ok()
+            .and_then(|bytes| FstMap::new(bytes).ok())
+        {
+            let built = Arc::new(BuiltIndex {
+                map,
+                entries,
+                generation: current_generation,
+            });
+            state.dirty_files.clear();
+            state.built = Some(built.clone());
+            Some(built)
+        } else {
+            state.built.clone()
+        }
+    }
+}
+
+fn flatten_outline(fs_file_path: &str, outline_nodes: &[OutlineNode]) -> Vec<SymbolIndexEntry> {
+    let mut entries = vec![];
+    for node in outline_nodes {
+        if node.is_class() {
+            push_entry(&mut entries, fs_file_path, node.content(), false);
+            for child in node.children() {
+                push_entry(&mut entries, fs_file_path, child, true);
+            }
+        } else {
+            push_entry(&mut entries, fs_file_path, node.content(), false);
+        }
+    }
+    entries
+}
+
+/// Classifies `content` and skips it entirely when the classification comes
+/// back synthetic, so generated/placeholder names never make it into the
+/// fuzzy index.
+fn push_entry(
+    entries: &mut Vec<SymbolIndexEntry>,
+    fs_file_path: &str,
+    content: &OutlineNodeContent,
+    is_member: bool,
+) {
+    if let Some(entry) = to_entry(fs_file_path, content, is_member) {
+        entries.push(entry);
+    }
+}
+
+fn to_entry(
+    fs_file_path: &str,
+    content: &OutlineNodeContent,
+    is_member: bool,
+) -> Option<SymbolIndexEntry> {
+    let name = content.name();
+    let kind = SymbolVisibilityClassifier::classify(&VisibilitySignals { name, is_member });
+    if kind.should_skip() {
+        return None;
+    }
+    Some(SymbolIndexEntry {
+        name: name.to_owned(),
+        file_path: fs_file_path.to_owned(),
+        range: content.range().clone(),
+        is_member,
+    })
+}
+
+// `OutlineNode`/`Range` (from `crate::chunking`) aren't available in this
+// tree, which rules out building real `SymbolIndexEntry`/`BuiltIndex`
+// fixtures here - so these tests stick to the pure distance function that
+// backs the fuzzy ranking rather than exercising `fuzzy_find` end to end.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_zero_for_identical_strings() {
+        assert_eq!(levenshtein_distance("handle_request", "handle_request"), 0);
+    }
+
+    #[test]
+    fn distance_matches_known_edit_counts() {
+        assert_eq!(levenshtein_distance("kitten", "sitting"), 3);
+        assert_eq!(levenshtein_distance("", "abc"), 3);
+        assert_eq!(levenshtein_distance("abc", ""), 3);
+    }
+
+    #[test]
+    fn distance_is_small_for_a_single_typo() {
+        assert_eq!(levenshtein_distance("handle_request", "handel_request"), 2);
+        assert_eq!(levenshtein_distance("handle_requests", "handle_request"), 1);
+    }
+
+    #[test]
+    fn distance_is_symmetric() {
+        assert_eq!(
+            levenshtein_distance("fuzzy_find", "fuzz_find"),
+            levenshtein_distance("fuzz_find", "fuzzy_find")
+        );
+    }
+}
+
+/// Plain Levenshtein edit distance, used only to rank the (small) candidate
+/// set the `fst` automaton already narrowed down - the automaton tells us
+/// *which* names are within range, not their exact distance.
+fn levenshtein_distance(a: &str, b: &str) -> u32 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut previous_row: Vec<u32> = (0..=b.len() as u32).collect();
+    let mut current_row = vec![0u32; b.len() + 1];
+    for (i, a_char) in a.iter().enumerate() {
+        current_row[0] = i as u32 + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let cost = if a_char == b_char { 0 } else { 1 };
+            current_row[j + 1] = (previous_row[j + 1] + 1)
+                .min(current_row[j] + 1)
+                .min(previous_row[j] + cost);
+        }
+        std::mem::swap(&mut previous_row, &mut current_row);
+    }
+    previous_row[b.len()]
+}
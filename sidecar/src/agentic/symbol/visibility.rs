@@ -0,0 +1,70 @@
+//! Classifies a resolved outline node's structural kind.
+//!
+//! `important_symbols` used to record only a symbol's name, range, and file
+//! path, so synthetic names (e.g. `..`-prefixed rest-pattern labels) leaked
+//! into exploration alongside real symbols. `SymbolVisibilityClassifier`
+//! flags those for skipping and tags whether a candidate is a top-level
+//! definition or a member.
+//!
+//! This used to also classify public vs. internal visibility, falling back
+//! to a leading-underscore heuristic whenever a declaration prefix wasn't
+//! available - which was every call site, since no outline node in this
+//! tree carries its declaration text. That made every non-underscored,
+//! private item look "public" and fed a wrong signal into disambiguation
+//! and fst ranking, so the distinction was dropped rather than shipped
+//! wrong.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// A top-level definition, not nested inside anything else.
+    TopLevel,
+    /// Nested inside another symbol (e.g. a method on a class/struct).
+    Member,
+    /// A compiler/linker-generated or otherwise synthetic name (e.g. a `..`
+    /// rest-pattern label) that should be skipped during exploration rather
+    /// than treated as a real symbol.
+    Synthetic,
+}
+
+impl SymbolKind {
+    /// Synthetic names are never worth exploring.
+    pub fn should_skip(&self) -> bool {
+        matches!(self, SymbolKind::Synthetic)
+    }
+}
+
+/// The cheap signals we can read off a candidate outline node to classify
+/// it, regardless of which language or tool response it came from.
+#[derive(Debug, Clone, Copy)]
+pub struct VisibilitySignals<'a> {
+    pub name: &'a str,
+    /// true when nested inside another symbol (e.g. a method on a class),
+    /// as opposed to a top-level definition.
+    pub is_member: bool,
+}
+
+pub struct SymbolVisibilityClassifier;
+
+impl SymbolVisibilityClassifier {
+    pub fn classify(signals: &VisibilitySignals) -> SymbolKind {
+        if is_synthetic_name(signals.name) {
+            SymbolKind::Synthetic
+        } else if signals.is_member {
+            SymbolKind::Member
+        } else {
+            SymbolKind::TopLevel
+        }
+    }
+}
+
+/// `..`-prefixed rest-pattern labels and `<...>`-wrapped placeholders (e.g.
+/// `<anonymous>`, `<closure@...>`) are how several outline parsers surface
+/// destructuring placeholders and other generated labels that don't
+/// correspond to a real symbol. Deliberately narrow: a bare leading `<` or
+/// `$` is common in real identifiers (generics, jQuery-style names) and
+/// isn't treated as synthetic on its own.
+fn is_synthetic_name(name: &str) -> bool {
+    name.is_empty()
+        || name.starts_with("..")
+        || (name.starts_with('<') && name.ends_with('>'))
+}
@@ -0,0 +1,188 @@
+//! Tracks the net effect of the editor changes observed for a file since a
+//! chat request was built, as a composed `OperationSeq` rather than a raw
+//! textual diff.
+//!
+//! `DiffRecentChanges` used to be built once, up front, from a point-in-time
+//! diff against whatever the buffer looked like when the request started -
+//! so if the user kept typing while `SessionChatClient::invoke` was
+//! mid-stream, the snapshot shown to the LLM (and any edit derived from its
+//! reply) was already stale. `record_editor_change` composes each newly
+//! reported `TextChange` onto the running op-seq instead, so
+//! `to_llm_client_message` always reflects every edit observed since
+//! `base_content`, and `rebase_llm_edit` can `transform` a change computed
+//! against `base_content` onto those same ops before it's applied back to
+//! the (now different) buffer.
+//!
+//! Tracks one file per instance. That's not a narrowing of some earlier
+//! shared type's scope - `OperationSeq` and `DiffRecentChanges` were both
+//! added together for this; a caller juggling several files keeps one
+//! `DiffRecentChanges` per path.
+
+use llm_client::clients::types::LLMClientMessage;
+
+use super::operation_seq::{Op, OperationSeq, TextChange};
+
+#[derive(Debug, Clone)]
+pub struct DiffRecentChanges {
+    fs_file_path: String,
+    base_content: String,
+    /// Every editor change observed since `base_content` was captured,
+    /// composed in order - this is what lets us `transform` an edit derived
+    /// from an older snapshot instead of just diffing two strings.
+    applied: OperationSeq,
+}
+
+impl DiffRecentChanges {
+    pub fn new(fs_file_path: String, base_content: String) -> Self {
+        let mut identity = OperationSeq::new();
+        identity.retain(base_content.chars().count());
+        Self {
+            fs_file_path,
+            base_content,
+            applied: identity,
+        }
+    }
+
+    /// Composes `change` onto the ops already recorded, so `current_content`
+    /// and `to_llm_client_message` pick it up.
+    pub fn record_editor_change(&mut self, change: TextChange) {
+        let current = self.current_content();
+        let change_op = OperationSeq::from_text_change(&current, &change);
+        self.applied = self.applied.compose(&change_op);
+    }
+
+    /// The buffer as it looks after every recorded change, i.e.
+    /// `self.applied` applied to `base_content`.
+    pub fn current_content(&self) -> String {
+        self.applied.apply(&self.base_content)
+    }
+
+    /// Rebases `llm_edit` - an op-seq computed against `base_content`, e.g.
+    /// derived from a reply built against the snapshot this struct started
+    /// with - onto every editor change recorded since, so applying the
+    /// result to `current_content()` lands cleanly instead of clobbering
+    /// those intervening edits. Returns `None` if `llm_edit` wasn't built
+    /// against `base_content` (its base length doesn't match).
+    ///
+    /// Nothing in this tree turns an LLM reply into a `TextChange` yet -
+    /// `SessionChatClient::invoke` only emits the reply as text - so today
+    /// this is exercised only by the tests below. It's the hook whatever
+    /// code eventually applies a suggested edit back to the editor should
+    /// call first.
+    pub fn rebase_llm_edit(&self, llm_edit: &OperationSeq) -> Option<OperationSeq> {
+        if llm_edit.base_len() != self.base_content.chars().count() {
+            return None;
+        }
+        let (_, llm_edit_prime) = OperationSeq::transform(&self.applied, llm_edit);
+        Some(llm_edit_prime)
+    }
+
+    pub fn to_llm_client_message(&self) -> Vec<LLMClientMessage> {
+        if self
+            .applied
+            .ops()
+            .iter()
+            .all(|op| matches!(op, Op::Retain(_)))
+        {
+            // nothing but a single Retain spanning the whole document means
+            // no editor changes have been recorded yet
+            return vec![];
+        }
+        let fs_file_path = &self.fs_file_path;
+        let change = self.render_change();
+        vec![LLMClientMessage::user(format!(
+            "<diff_recent_changes>\n<fs_file_path>\n{fs_file_path}\n</fs_file_path>\n<change>\n{change}\n</change>\n</diff_recent_changes>"
+        ))]
+    }
+
+    /// Renders `self.applied` against `base_content` as a word-diff: spans
+    /// the ops retain pass through unchanged, deleted spans are wrapped
+    /// `[-...-]`, inserted spans `{+...+}` - so the LLM sees what changed
+    /// instead of the whole current file.
+    fn render_change(&self) -> String {
+        let base_chars: Vec<char> = self.base_content.chars().collect();
+        let mut rendered = String::new();
+        let mut index = 0;
+        for op in self.applied.ops() {
+            match op {
+                Op::Retain(n) => {
+                    rendered.extend(&base_chars[index..index + n]);
+                    index += n;
+                }
+                Op::Delete(n) => {
+                    rendered.push_str("[-");
+                    rendered.extend(&base_chars[index..index + n]);
+                    rendered.push_str("-]");
+                    index += n;
+                }
+                Op::Insert(s) => {
+                    rendered.push_str("{+");
+                    rendered.push_str(s);
+                    rendered.push_str("+}");
+                }
+            }
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_by_default_with_no_recorded_changes() {
+        let diff = DiffRecentChanges::new("foo.rs".to_owned(), "fn main() {}".to_owned());
+        assert!(diff.to_llm_client_message().is_empty());
+        assert_eq!(diff.current_content(), "fn main() {}");
+    }
+
+    #[test]
+    fn records_and_composes_multiple_editor_changes() {
+        let mut diff = DiffRecentChanges::new("foo.rs".to_owned(), "fn main() {}".to_owned());
+        diff.record_editor_change(TextChange::new(9..9, " todo!()".to_owned()));
+        assert_eq!(diff.current_content(), "fn main() todo!() {}");
+        diff.record_editor_change(TextChange::new(0..2, "pub fn".to_owned()));
+        assert_eq!(diff.current_content(), "pub fn main() todo!() {}");
+        assert!(!diff.to_llm_client_message().is_empty());
+    }
+
+    #[test]
+    fn rendered_change_marks_inserts_and_deletes_instead_of_the_whole_file() {
+        let mut diff = DiffRecentChanges::new("foo.rs".to_owned(), "fn main() {}".to_owned());
+        diff.record_editor_change(TextChange::new(9..9, " todo!()".to_owned()));
+        assert_eq!(diff.render_change(), "fn main(){+ todo!()+} {}");
+        assert_eq!(diff.to_llm_client_message().len(), 1);
+    }
+
+    #[test]
+    fn rebases_an_llm_edit_against_recorded_editor_changes() {
+        let base = "fn main() {}";
+        let mut diff = DiffRecentChanges::new("foo.rs".to_owned(), base.to_owned());
+        // the editor inserted a doc comment above the function while we were
+        // streaming a reply
+        diff.record_editor_change(TextChange::new(0..0, "/// doc\n".to_owned()));
+
+        // the LLM's suggested edit was derived against the original `base`,
+        // before that doc comment existed
+        let llm_edit = OperationSeq::from_text_change(
+            base,
+            &TextChange::new(10..12, "{ println!(\"hi\"); }".to_owned()),
+        );
+        let rebased = diff
+            .rebase_llm_edit(&llm_edit)
+            .expect("llm_edit was built against base_content");
+        assert_eq!(
+            rebased.apply(&diff.current_content()),
+            "/// doc\nfn main() { println!(\"hi\"); }"
+        );
+    }
+
+    #[test]
+    fn rebase_rejects_an_edit_built_against_a_different_base() {
+        let diff = DiffRecentChanges::new("foo.rs".to_owned(), "fn main() {}".to_owned());
+        let mismatched_edit =
+            OperationSeq::from_text_change("totally different length", &TextChange::new(0..0, "x".to_owned()));
+        assert!(diff.rebase_llm_edit(&mismatched_edit).is_none());
+    }
+}
@@ -0,0 +1,420 @@
+//! Operational-transform primitives for editor-friendly text changes.
+//!
+//! A raw textual diff is a single point-in-time snapshot, so a change
+//! computed while `SessionChatClient::invoke` is mid-stream can go stale the
+//! moment the user keeps typing: the edit was derived against a buffer that
+//! no longer exists. `OperationSeq` gives us something we can `compose` and
+//! `transform` instead of a single point-in-time diff, so a change derived
+//! from the LLM's reply can be rebased onto whatever the editor reported
+//! since the request started, the same way collaborative editors reconcile
+//! concurrent edits.
+
+use std::ops::Range;
+
+/// A single edit reported by the editor: replace `range` with `content`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChange {
+    pub range: Range<usize>,
+    pub content: String,
+}
+
+impl TextChange {
+    pub fn new(range: Range<usize>, content: String) -> Self {
+        Self { range, content }
+    }
+}
+
+/// One step of an operational-transform sequence. `Retain`/`Delete` are
+/// measured in unicode scalar values (chars), matching `TextChange::range`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+/// A list of ops whose combined pre-image length equals the length of the
+/// document it was built against. Applying every op in order reproduces the
+/// post-image.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OperationSeq {
+    ops: Vec<Op>,
+}
+
+impl OperationSeq {
+    pub fn new() -> Self {
+        Self { ops: vec![] }
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    pub fn retain(&mut self, count: usize) -> &mut Self {
+        if count == 0 {
+            return self;
+        }
+        if let Some(Op::Retain(last)) = self.ops.last_mut() {
+            *last += count;
+        } else {
+            self.ops.push(Op::Retain(count));
+        }
+        self
+    }
+
+    pub fn insert(&mut self, content: impl Into<String>) -> &mut Self {
+        let content = content.into();
+        if content.is_empty() {
+            return self;
+        }
+        if let Some(Op::Insert(last)) = self.ops.last_mut() {
+            last.push_str(&content);
+        } else {
+            self.ops.push(Op::Insert(content));
+        }
+        self
+    }
+
+    pub fn delete(&mut self, count: usize) -> &mut Self {
+        if count == 0 {
+            return self;
+        }
+        if let Some(Op::Delete(last)) = self.ops.last_mut() {
+            *last += count;
+        } else {
+            self.ops.push(Op::Delete(count));
+        }
+        self
+    }
+
+    /// Length of the document this op-seq expects to be applied to.
+    pub fn base_len(&self) -> usize {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                Op::Retain(n) | Op::Delete(n) => *n,
+                Op::Insert(_) => 0,
+            })
+            .sum()
+    }
+
+    /// Length of the document produced by applying this op-seq.
+    pub fn target_len(&self) -> usize {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                Op::Retain(n) => *n,
+                Op::Insert(s) => s.chars().count(),
+                Op::Delete(_) => 0,
+            })
+            .sum()
+    }
+
+    /// Builds an op-seq over `base` that applies a single `TextChange`.
+    pub fn from_text_change(base: &str, change: &TextChange) -> Self {
+        let base_len = base.chars().count();
+        let mut op_seq = OperationSeq::new();
+        op_seq.retain(change.range.start);
+        op_seq.delete(change.range.end.saturating_sub(change.range.start));
+        op_seq.insert(change.content.clone());
+        op_seq.retain(base_len.saturating_sub(change.range.end));
+        op_seq
+    }
+
+    /// Applies this op-seq to `base`, returning the resulting document.
+    /// Panics if `base`'s length doesn't match `self.base_len()`, since that
+    /// means the op-seq was built against a different document.
+    pub fn apply(&self, base: &str) -> String {
+        let base_chars: Vec<char> = base.chars().collect();
+        assert_eq!(
+            base_chars.len(),
+            self.base_len(),
+            "OperationSeq::apply called with a base document of the wrong length"
+        );
+        let mut result = String::new();
+        let mut index = 0;
+        for op in &self.ops {
+            match op {
+                Op::Retain(n) => {
+                    result.extend(&base_chars[index..index + n]);
+                    index += n;
+                }
+                Op::Delete(n) => {
+                    index += n;
+                }
+                Op::Insert(s) => {
+                    result.push_str(s);
+                }
+            }
+        }
+        result
+    }
+
+    /// Merges `self` followed by `other` (`other` was built against the
+    /// document `self` produces) into a single equivalent op-seq.
+    pub fn compose(&self, other: &OperationSeq) -> OperationSeq {
+        assert_eq!(
+            self.target_len(),
+            other.base_len(),
+            "compose requires other to be built against self's target document"
+        );
+        let mut result = OperationSeq::new();
+        let mut ops_a = self.ops.iter().cloned().peekable();
+        let mut ops_b = other.ops.iter().cloned().peekable();
+        let mut a = ops_a.next();
+        let mut b = ops_b.next();
+        loop {
+            match (a.take(), b.take()) {
+                (None, None) => break,
+                (Some(Op::Delete(n)), b_op) => {
+                    result.delete(n);
+                    a = ops_a.next();
+                    b = b_op;
+                }
+                (a_op, Some(Op::Insert(s))) => {
+                    result.insert(s);
+                    a = a_op;
+                    b = ops_b.next();
+                }
+                (Some(Op::Insert(s)), Some(Op::Retain(n))) => {
+                    let s_len = s.chars().count();
+                    if s_len <= n {
+                        result.insert(s);
+                        a = ops_a.next();
+                        b = if n == s_len {
+                            ops_b.next()
+                        } else {
+                            Some(Op::Retain(n - s_len))
+                        };
+                    } else {
+                        let (head, tail) = split_str(&s, n);
+                        result.insert(head);
+                        a = Some(Op::Insert(tail));
+                        b = ops_b.next();
+                    }
+                }
+                (Some(Op::Insert(s)), Some(Op::Delete(n))) => {
+                    let s_len = s.chars().count();
+                    if s_len <= n {
+                        a = ops_a.next();
+                        b = if n == s_len {
+                            ops_b.next()
+                        } else {
+                            Some(Op::Delete(n - s_len))
+                        };
+                    } else {
+                        let (_, tail) = split_str(&s, n);
+                        a = Some(Op::Insert(tail));
+                        b = ops_b.next();
+                    }
+                }
+                (Some(Op::Retain(n)), Some(Op::Retain(m))) => {
+                    let min = n.min(m);
+                    result.retain(min);
+                    a = remainder(Op::Retain(n), min, &mut ops_a);
+                    b = remainder(Op::Retain(m), min, &mut ops_b);
+                }
+                (Some(Op::Retain(n)), Some(Op::Delete(m))) => {
+                    let min = n.min(m);
+                    result.delete(min);
+                    a = remainder(Op::Retain(n), min, &mut ops_a);
+                    b = remainder(Op::Delete(m), min, &mut ops_b);
+                }
+                (None, Some(op)) | (Some(op), None) => {
+                    // one side ran out first; this only happens when the two
+                    // op-seqs disagree on lengths, which the asserts above
+                    // should have already ruled out
+                    match op {
+                        Op::Retain(n) => result.retain(n),
+                        Op::Insert(s) => result.insert(s),
+                        Op::Delete(n) => result.delete(n),
+                    };
+                    a = ops_a.next();
+                    b = ops_b.next();
+                }
+            }
+        }
+        result
+    }
+
+    /// Given two concurrent op-seqs built against the same base document,
+    /// produces `(a', b')` such that applying `a` then `b'` converges with
+    /// applying `b` then `a'`. Concurrent inserts at the same position are
+    /// ordered with `a`'s insert first, a fixed, deterministic tie-break.
+    pub fn transform(a: &OperationSeq, b: &OperationSeq) -> (OperationSeq, OperationSeq) {
+        assert_eq!(
+            a.base_len(),
+            b.base_len(),
+            "transform requires both op-seqs to share a base document"
+        );
+        let mut a_prime = OperationSeq::new();
+        let mut b_prime = OperationSeq::new();
+        let mut ops_a = a.ops.iter().cloned().peekable();
+        let mut ops_b = b.ops.iter().cloned().peekable();
+        let mut op_a = ops_a.next();
+        let mut op_b = ops_b.next();
+        loop {
+            match (op_a.take(), op_b.take()) {
+                (None, None) => break,
+                (Some(Op::Insert(s)), op_b_rest) => {
+                    // a's insert comes first by convention
+                    a_prime.insert(s.clone());
+                    b_prime.retain(s.chars().count());
+                    op_a = ops_a.next();
+                    op_b = op_b_rest;
+                }
+                (op_a_rest, Some(Op::Insert(s))) => {
+                    a_prime.retain(s.chars().count());
+                    b_prime.insert(s.clone());
+                    op_a = op_a_rest;
+                    op_b = ops_b.next();
+                }
+                (Some(Op::Retain(n)), Some(Op::Retain(m))) => {
+                    let min = n.min(m);
+                    a_prime.retain(min);
+                    b_prime.retain(min);
+                    op_a = remainder(Op::Retain(n), min, &mut ops_a);
+                    op_b = remainder(Op::Retain(m), min, &mut ops_b);
+                }
+                (Some(Op::Delete(n)), Some(Op::Delete(m))) => {
+                    // both deleted the same region, cancels out on both sides
+                    let min = n.min(m);
+                    op_a = remainder(Op::Delete(n), min, &mut ops_a);
+                    op_b = remainder(Op::Delete(m), min, &mut ops_b);
+                }
+                (Some(Op::Delete(n)), Some(Op::Retain(m))) => {
+                    let min = n.min(m);
+                    a_prime.delete(min);
+                    op_a = remainder(Op::Delete(n), min, &mut ops_a);
+                    op_b = remainder(Op::Retain(m), min, &mut ops_b);
+                }
+                (Some(Op::Retain(n)), Some(Op::Delete(m))) => {
+                    let min = n.min(m);
+                    b_prime.delete(min);
+                    op_a = remainder(Op::Retain(n), min, &mut ops_a);
+                    op_b = remainder(Op::Delete(m), min, &mut ops_b);
+                }
+                (None, Some(op)) | (Some(op), None) => {
+                    // lengths disagreed; the asserts above should prevent this
+                    match op {
+                        Op::Retain(n) => {
+                            a_prime.retain(n);
+                            b_prime.retain(n);
+                        }
+                        Op::Delete(n) => {
+                            a_prime.delete(n);
+                            b_prime.delete(n);
+                        }
+                        Op::Insert(_) => unreachable!("inserts are handled above"),
+                    }
+                    op_a = ops_a.next();
+                    op_b = ops_b.next();
+                }
+            }
+        }
+        (a_prime, b_prime)
+    }
+}
+
+/// Splits `s` after `count` chars, returning owned `(head, tail)` strings.
+fn split_str(s: &str, count: usize) -> (String, String) {
+    let split_at = s
+        .char_indices()
+        .nth(count)
+        .map(|(byte_index, _)| byte_index)
+        .unwrap_or(s.len());
+    (s[..split_at].to_owned(), s[split_at..].to_owned())
+}
+
+/// After consuming `consumed` units of `op`, returns whatever is left of it,
+/// pulling the next op off `rest` if nothing is left.
+fn remainder(
+    op: Op,
+    consumed: usize,
+    rest: &mut std::iter::Peekable<impl Iterator<Item = Op>>,
+) -> Option<Op> {
+    match op {
+        Op::Retain(n) if n > consumed => Some(Op::Retain(n - consumed)),
+        Op::Delete(n) if n > consumed => Some(Op::Delete(n - consumed)),
+        _ => rest.next(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(base: &str, range: Range<usize>, content: &str) -> OperationSeq {
+        OperationSeq::from_text_change(base, &TextChange::new(range, content.to_owned()))
+    }
+
+    #[test]
+    fn compose_merges_sequential_ops() {
+        let base = "hello world";
+        let first = change(base, 6..11, "there");
+        let mid = first.apply(base);
+        assert_eq!(mid, "hello there");
+        let second = change(&mid, 0..5, "hi");
+        let composed = first.compose(&second);
+        assert_eq!(composed.apply(base), second.apply(&mid));
+        assert_eq!(composed.apply(base), "hi there");
+    }
+
+    #[test]
+    fn transform_converges_on_concurrent_non_overlapping_edits() {
+        let base = "hello world";
+        // one side inserts at the start, the other appends at the end -
+        // disjoint edits, so both orderings must produce the same text
+        let a = change(base, 0..0, "say: ");
+        let b = change(base, 11..11, "!");
+        let (a_prime, b_prime) = OperationSeq::transform(&a, &b);
+        let via_a_then_b_prime = b_prime.apply(&a.apply(base));
+        let via_b_then_a_prime = a_prime.apply(&b.apply(base));
+        assert_eq!(via_a_then_b_prime, via_b_then_a_prime);
+        assert_eq!(via_a_then_b_prime, "say: hello world!");
+    }
+
+    #[test]
+    fn transform_converges_on_concurrent_inserts_at_same_position() {
+        let base = "hello world";
+        let a = change(base, 5..5, "-A");
+        let b = change(base, 5..5, "-B");
+        let (a_prime, b_prime) = OperationSeq::transform(&a, &b);
+        let via_a_then_b_prime = b_prime.apply(&a.apply(base));
+        let via_b_then_a_prime = a_prime.apply(&b.apply(base));
+        assert_eq!(via_a_then_b_prime, via_b_then_a_prime);
+        // a's insert wins the tie-break and comes first, on both paths
+        assert_eq!(via_a_then_b_prime, "hello-A-B world");
+    }
+
+    #[test]
+    fn transform_converges_on_overlapping_deletes() {
+        let base = "hello world";
+        let a = change(base, 0..5, ""); // deletes "hello"
+        let b = change(base, 3..8, ""); // deletes "lo wo"
+        let (a_prime, b_prime) = OperationSeq::transform(&a, &b);
+        let via_a_then_b_prime = b_prime.apply(&a.apply(base));
+        let via_b_then_a_prime = a_prime.apply(&b.apply(base));
+        assert_eq!(via_a_then_b_prime, via_b_then_a_prime);
+    }
+
+    #[test]
+    fn split_str_splits_on_char_boundaries_not_bytes() {
+        // `é` and every char of `日本語` are multi-byte in utf-8; splitting
+        // after N *chars* must not panic or cut a codepoint in half
+        let (head, tail) = split_str("héllo", 2);
+        assert_eq!(head, "hé");
+        assert_eq!(tail, "llo");
+
+        let (head, tail) = split_str("日本語", 1);
+        assert_eq!(head, "日");
+        assert_eq!(tail, "本語");
+    }
+
+    #[test]
+    fn from_text_change_handles_multibyte_content() {
+        let base = "café";
+        let op = change(base, 4..4, "!");
+        assert_eq!(op.apply(base), "café!");
+    }
+}
@@ -0,0 +1,66 @@
+//! Fan-out hub so several connected editors can watch the same chat session.
+//!
+//! `SessionChatClient::invoke` used to stream deltas to a single
+//! `UnboundedSender<UIEventWithID>`, so only the client that started the
+//! request could see the reply as it streamed in. `SessionBroadcaster` keeps
+//! a `tokio::sync::broadcast` channel per `session_id` so, e.g., two editor
+//! windows or a pair-programming peer can all subscribe to the same live
+//! deltas.
+
+use std::{collections::HashMap, sync::Arc};
+
+use tokio::sync::Mutex;
+use tokio_stream::wrappers::BroadcastStream;
+
+use crate::agentic::symbol::ui_event::UIEventWithID;
+
+const SESSION_BROADCAST_CAPACITY: usize = 256;
+
+#[derive(Default)]
+pub struct SessionBroadcaster {
+    channels: Mutex<HashMap<String, tokio::sync::broadcast::Sender<UIEventWithID>>>,
+    // so a client that subscribes mid-stream still gets caught up instead of
+    // waiting for the next delta
+    latest_snapshot: Mutex<HashMap<String, UIEventWithID>>,
+}
+
+impl SessionBroadcaster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    async fn sender_for(&self, session_id: &str) -> tokio::sync::broadcast::Sender<UIEventWithID> {
+        let mut channels = self.channels.lock().await;
+        channels
+            .entry(session_id.to_owned())
+            .or_insert_with(|| tokio::sync::broadcast::channel(SESSION_BROADCAST_CAPACITY).0)
+            .clone()
+    }
+
+    /// Publishes `event` to every current subscriber of `session_id` and
+    /// keeps it as the session's latest snapshot. With nobody subscribed yet
+    /// the live send reaches no one, but a later `subscribe` still gets the
+    /// event back as that snapshot.
+    pub async fn publish(&self, session_id: &str, event: UIEventWithID) {
+        self.latest_snapshot
+            .lock()
+            .await
+            .insert(session_id.to_owned(), event.clone());
+        let sender = self.sender_for(session_id).await;
+        let _ = sender.send(event);
+    }
+
+    /// Subscribes to `session_id`'s live deltas. Returns the most recently
+    /// published event (if any) alongside the stream, so a late joiner can
+    /// be caught up to `answer_up_until_now` before the next delta arrives.
+    pub async fn subscribe(
+        &self,
+        session_id: &str,
+    ) -> (Option<UIEventWithID>, BroadcastStream<UIEventWithID>) {
+        let snapshot = self.latest_snapshot.lock().await.get(session_id).cloned();
+        let sender = self.sender_for(session_id).await;
+        (snapshot, BroadcastStream::new(sender.subscribe()))
+    }
+}
+
+pub type SharedSessionBroadcaster = Arc<SessionBroadcaster>;
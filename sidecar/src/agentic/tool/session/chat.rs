@@ -30,16 +30,22 @@ use llm_client::{
 };
 use tokio::sync::mpsc::UnboundedSender;
 
-#[derive(Debug, Clone, serde::Serialize)]
+use super::broadcaster::SharedSessionBroadcaster;
+use super::chat_store::SharedChatSessionStore;
+use super::dialogue_state::ChatDialogueState;
+use super::lamport::{Lamport, SharedSessionLamportClock};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum SessionChatRole {
     User,
     Assistant,
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SessionChatMessage {
     message: String,
     role: SessionChatRole,
+    exchange_id: Option<String>,
 }
 
 impl SessionChatMessage {
@@ -47,6 +53,7 @@ impl SessionChatMessage {
         Self {
             message,
             role: SessionChatRole::Assistant,
+            exchange_id: None,
         }
     }
 
@@ -58,12 +65,24 @@ impl SessionChatMessage {
         Self {
             message,
             role: SessionChatRole::User,
+            exchange_id: None,
         }
     }
 
     pub fn role(&self) -> &SessionChatRole {
         &self.role
     }
+
+    /// Stamps the exchange this message belongs to, so a store can later
+    /// `truncate` a session back to a particular point in the conversation.
+    pub fn with_exchange_id(mut self, exchange_id: String) -> Self {
+        self.exchange_id = Some(exchange_id);
+        self
+    }
+
+    pub fn exchange_id(&self) -> Option<&str> {
+        self.exchange_id.as_deref()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -78,6 +97,8 @@ pub struct SessionChatClientRequest {
     ui_sender: UnboundedSender<UIEventWithID>,
     cancellation_token: tokio_util::sync::CancellationToken,
     access_token: String,
+    dialogue_state: ChatDialogueState,
+    lamport_stamp: Lamport,
 }
 
 impl SessionChatClientRequest {
@@ -92,6 +113,8 @@ impl SessionChatClientRequest {
         ui_sender: UnboundedSender<UIEventWithID>,
         cancellation_token: tokio_util::sync::CancellationToken,
         access_token: String,
+        dialogue_state: ChatDialogueState,
+        lamport_stamp: Lamport,
     ) -> Self {
         Self {
             diff_recent_edits,
@@ -100,32 +123,85 @@ impl SessionChatClientRequest {
             session_id,
             exchange_id,
             repo_ref,
+            dialogue_state,
+            lamport_stamp,
             project_labels,
             ui_sender,
             cancellation_token,
             access_token,
         }
     }
+
+    pub fn dialogue_state(&self) -> ChatDialogueState {
+        self.dialogue_state
+    }
+
+    pub fn lamport_stamp(&self) -> Lamport {
+        self.lamport_stamp
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct SessionChatClientResponse {
     reply: String,
+    next_dialogue_state: ChatDialogueState,
 }
 
 impl SessionChatClientResponse {
     pub fn reply(self) -> String {
         self.reply
     }
+
+    /// The state the session should carry into its next turn, as decided by
+    /// `ChatDialogueState::next`.
+    pub fn next_dialogue_state(&self) -> ChatDialogueState {
+        self.next_dialogue_state
+    }
 }
 
 pub struct SessionChatClient {
     llm_client: Arc<LLMBroker>,
+    chat_session_store: SharedChatSessionStore,
+    broadcaster: SharedSessionBroadcaster,
+    lamport_clock: SharedSessionLamportClock,
 }
 
 impl SessionChatClient {
-    pub fn new(llm_client: Arc<LLMBroker>) -> Self {
-        Self { llm_client }
+    pub fn new(
+        llm_client: Arc<LLMBroker>,
+        chat_session_store: SharedChatSessionStore,
+        broadcaster: SharedSessionBroadcaster,
+        lamport_clock: SharedSessionLamportClock,
+    ) -> Self {
+        Self {
+            llm_client,
+            chat_session_store,
+            broadcaster,
+            lamport_clock,
+        }
+    }
+
+    /// Call this whenever the editor reports a change to a buffer this
+    /// session is tracking: composes `change` into `diff_recent_edits` and
+    /// bumps the session's Lamport clock, so the `is_stale` check in
+    /// `invoke` can actually catch a request whose `DiffRecentChanges`
+    /// snapshot the editor has since moved past. Returns the stamp the
+    /// caller should attach to the next `SessionChatClientRequest` it
+    /// builds for this session.
+    pub async fn observe_editor_change(
+        &self,
+        session_id: &str,
+        actor: uuid::Uuid,
+        diff_recent_edits: &mut DiffRecentChanges,
+        change: crate::agentic::tool::helpers::operation_seq::TextChange,
+    ) -> Lamport {
+        diff_recent_edits.record_editor_change(change);
+        let observed = self
+            .lamport_clock
+            .latest(session_id)
+            .await
+            .unwrap_or_else(|| Lamport::zero(actor));
+        self.lamport_clock.observe_edit(session_id, observed, actor).await
     }
 
     fn system_message(&self, context: &SessionChatClientRequest) -> String {
@@ -189,7 +265,10 @@ Respect these rules at all times:
     1. // rest of code ..
     2. // rest of code ..
     ```
-    Here the codeblock has line numbers 1 and 2, do not write the line numbers in the codeblock"#
+    Here the codeblock has line numbers 1 and 2, do not write the line numbers in the codeblock
+
+{dialogue_state_rule}"#,
+            dialogue_state_rule = context.dialogue_state.system_prompt_fragment(),
         );
         system_message
     }
@@ -236,8 +315,39 @@ impl Tool for SessionChatClient {
         let ui_sender = context.ui_sender.clone();
         let root_id = context.session_id.to_owned();
         let exchange_id = context.exchange_id.to_owned();
+        let dialogue_state = context.dialogue_state;
+
+        // the editor may have moved on since this request's `DiffRecentChanges`
+        // snapshot was taken; refuse to emit a reply that could reference
+        // stale line numbers and ask the caller to re-sync instead
+        if self
+            .lamport_clock
+            .is_stale(&root_id, context.lamport_stamp)
+            .await
+        {
+            return Err(ToolError::StaleContext(root_id));
+        }
+
         let system_message = LLMClientMessage::system(self.system_message(&context)).cache_point();
 
+        // persist the user's turn before we start streaming, so the
+        // conversation survives a restart even if the reply never completes
+        let latest_user_reply = context
+            .previous_messages
+            .last()
+            .filter(|message| matches!(message.role(), SessionChatRole::User))
+            .map(|message| message.message().to_owned());
+        if let Some(latest_user_reply) = latest_user_reply.as_ref() {
+            self.chat_session_store
+                .append(
+                    &root_id,
+                    SessionChatMessage::user(latest_user_reply.to_owned())
+                        .with_exchange_id(exchange_id.to_owned()),
+                )
+                .await;
+        }
+        let latest_user_reply = latest_user_reply.unwrap_or_default();
+
         // so now chat will be routed through codestory provider
         let codestory_access_token = CodestoryAccessToken {
             access_token: context.access_token.clone(),
@@ -258,14 +368,20 @@ impl Tool for SessionChatClient {
 
         println!("{:?}", &messages);
 
-        let request =
-            LLMClientCompletionRequest::new(llm_properties.llm().clone(), messages, 0.2, None);
+        let request = LLMClientCompletionRequest::new(
+            llm_properties.llm().clone(),
+            messages,
+            dialogue_state.temperature(),
+            None,
+        );
 
         // now we have to poll both the stream which will send deltas and also the one
         // which will poll the future from the stream
         let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
         let cloned_llm_client = self.llm_client.clone();
         let cloned_root_id = root_id.to_owned();
+        let polling_root_id = root_id.to_owned();
+        let polling_exchange_id = exchange_id.to_owned();
         let llm_response = run_with_cancellation(
             cancellation_token,
             tokio::spawn(async move {
@@ -287,20 +403,35 @@ impl Tool for SessionChatClient {
         );
 
         // now poll from the receiver where we are getting deltas
+        let broadcaster = self.broadcaster.clone();
         let polling_llm_response = tokio::spawn(async move {
             let ui_sender = ui_sender;
-            let request_id = root_id;
-            let exchange_id = exchange_id;
+            let request_id = polling_root_id;
+            let exchange_id = polling_exchange_id;
             let mut answer_up_until_now = "".to_owned();
             let mut delta = tokio_stream::wrappers::UnboundedReceiverStream::new(receiver);
             while let Some(stream_msg) = delta.next().await {
                 answer_up_until_now = stream_msg.answer_up_until_now().to_owned();
-                let _ = ui_sender.send(UIEventWithID::chat_event(
+                // `Plan`/`Clarify`/`Verify` must not hand back code edits, so
+                // strip fenced code before this delta goes anywhere a client
+                // can see it - stripping only the final stored/returned reply
+                // would still leak every code block the model streamed out
+                // along the way.
+                let published_answer = if dialogue_state.allows_code_edits() {
+                    answer_up_until_now.clone()
+                } else {
+                    strip_disallowed_code_edits(&answer_up_until_now)
+                };
+                let chat_event = UIEventWithID::chat_event(
                     request_id.to_owned(),
                     exchange_id.to_owned(),
-                    stream_msg.answer_up_until_now().to_owned(),
+                    published_answer,
                     stream_msg.delta().map(|delta| delta.to_owned()),
-                ));
+                );
+                // other clients subscribed to this session (a second editor
+                // window, a pair-programming peer, ...) get the same deltas
+                broadcaster.publish(&request_id, chat_event.clone()).await;
+                let _ = ui_sender.send(chat_event);
             }
             answer_up_until_now
         });
@@ -312,10 +443,81 @@ impl Tool for SessionChatClient {
         // wait for the delta streaming to finish
         let answer_up_until_now = polling_llm_response.await;
         match answer_up_until_now {
-            Ok(response) => Ok(ToolOutput::context_driven_chat_reply(
-                SessionChatClientResponse { reply: response },
-            )),
+            Ok(response) => {
+                // `Plan` (and every other non-`Edit` state) must not hand back
+                // code edits, per the dialogue_state_rule baked into the
+                // system prompt above - code blocks are how this tool
+                // presents an edit to the user, so stripping them here is a
+                // real backstop instead of relying on the model to honour
+                // the prompt text.
+                let response = if dialogue_state.allows_code_edits() {
+                    response
+                } else {
+                    strip_disallowed_code_edits(&response)
+                };
+                self.chat_session_store
+                    .append(
+                        &root_id,
+                        SessionChatMessage::assistant(response.to_owned())
+                            .with_exchange_id(exchange_id.to_owned()),
+                    )
+                    .await;
+                let next_dialogue_state = dialogue_state.next(&response, &latest_user_reply);
+                Ok(ToolOutput::context_driven_chat_reply(
+                    SessionChatClientResponse {
+                        reply: response,
+                        next_dialogue_state,
+                    },
+                ))
+            }
             _ => Err(ToolError::RetriesExhausted),
         }
     }
 }
+
+/// Replaces every fenced code block in `reply` with a short placeholder, so a
+/// state that isn't `Edit` can't hand a code edit back to the user no matter
+/// what the model did with the prompt's instructions.
+fn strip_disallowed_code_edits(reply: &str) -> String {
+    let mut out = String::with_capacity(reply.len());
+    let mut lines = reply.lines().peekable();
+    let mut in_fence = false;
+    while let Some(line) = lines.next() {
+        if line.trim_start().starts_with("```") {
+            if !in_fence {
+                out.push_str("`(code edit omitted - not in the Edit state)`");
+                out.push('\n');
+            }
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        out.push_str(line);
+        if lines.peek().is_some() {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_fenced_code_blocks() {
+        let reply = "Here's the change:\n```rust\nfn foo() {}\n```\nDone.";
+        let stripped = strip_disallowed_code_edits(reply);
+        assert!(!stripped.contains("fn foo"));
+        assert!(stripped.contains("Here's the change"));
+        assert!(stripped.contains("Done."));
+    }
+
+    #[test]
+    fn leaves_prose_without_code_blocks_untouched() {
+        let reply = "No code here, just an explanation.";
+        assert_eq!(strip_disallowed_code_edits(reply), reply);
+    }
+}
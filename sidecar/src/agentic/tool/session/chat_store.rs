@@ -0,0 +1,172 @@
+//! Persistent storage for session chat history.
+//!
+//! `SessionChatClient` used to keep `previous_messages` entirely in memory, so a
+//! sidecar restart would lose all conversation context. `ChatSessionStore`
+//! abstracts over where the history actually lives, so swapping the backing
+//! store is just a matter of implementing `load`/`append`/`truncate`.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt},
+    sync::Mutex,
+};
+
+use super::chat::SessionChatMessage;
+
+#[async_trait]
+pub trait ChatSessionStore: Send + Sync {
+    /// Loads every message recorded so far for `session_id`, in the order
+    /// they were appended.
+    async fn load(&self, session_id: &str) -> Vec<SessionChatMessage>;
+
+    /// Persists a single message onto the end of `session_id`'s history.
+    async fn append(&self, session_id: &str, message: SessionChatMessage);
+
+    /// Drops every message recorded after `exchange_id`, so a session can be
+    /// resumed or branched from an earlier point in the conversation.
+    async fn truncate(&self, session_id: &str, exchange_id: &str);
+}
+
+/// Default store, kept purely in process memory. Equivalent to the old
+/// behaviour where history did not survive a restart.
+#[derive(Default)]
+pub struct InMemoryChatSessionStore {
+    sessions: Mutex<HashMap<String, Vec<SessionChatMessage>>>,
+}
+
+impl InMemoryChatSessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ChatSessionStore for InMemoryChatSessionStore {
+    async fn load(&self, session_id: &str) -> Vec<SessionChatMessage> {
+        self.sessions
+            .lock()
+            .await
+            .get(session_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    async fn append(&self, session_id: &str, message: SessionChatMessage) {
+        self.sessions
+            .lock()
+            .await
+            .entry(session_id.to_owned())
+            .or_default()
+            .push(message);
+    }
+
+    async fn truncate(&self, session_id: &str, exchange_id: &str) {
+        if let Some(messages) = self.sessions.lock().await.get_mut(session_id) {
+            truncate_after_exchange(messages, exchange_id);
+        }
+    }
+}
+
+/// File-backed store which keeps one JSON-lines file per `session_id`, so
+/// history survives a sidecar restart and can be inspected/edited on disk.
+pub struct FileChatSessionStore {
+    root_directory: PathBuf,
+    // guards read-modify-write sequences (mainly `truncate`) against
+    // concurrent appends to the same session file
+    write_lock: Mutex<()>,
+}
+
+impl FileChatSessionStore {
+    pub fn new(root_directory: PathBuf) -> Self {
+        Self {
+            root_directory,
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    fn session_file_path(&self, session_id: &str) -> PathBuf {
+        self.root_directory.join(format!("{session_id}.jsonl"))
+    }
+
+    async fn read_all(&self, session_id: &str) -> Vec<SessionChatMessage> {
+        let file_path = self.session_file_path(session_id);
+        let Ok(file) = tokio::fs::File::open(&file_path).await else {
+            return vec![];
+        };
+        let mut lines = tokio::io::BufReader::new(file).lines();
+        let mut messages = vec![];
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Ok(message) = serde_json::from_str::<SessionChatMessage>(&line) {
+                messages.push(message);
+            }
+        }
+        messages
+    }
+
+    async fn write_all(&self, session_id: &str, messages: &[SessionChatMessage]) {
+        let _ = tokio::fs::create_dir_all(&self.root_directory).await;
+        let file_path = self.session_file_path(session_id);
+        let mut contents = String::new();
+        for message in messages {
+            if let Ok(serialized) = serde_json::to_string(message) {
+                contents.push_str(&serialized);
+                contents.push('\n');
+            }
+        }
+        let _ = tokio::fs::write(file_path, contents).await;
+    }
+}
+
+#[async_trait]
+impl ChatSessionStore for FileChatSessionStore {
+    async fn load(&self, session_id: &str) -> Vec<SessionChatMessage> {
+        self.read_all(session_id).await
+    }
+
+    async fn append(&self, session_id: &str, message: SessionChatMessage) {
+        let _guard = self.write_lock.lock().await;
+        let _ = tokio::fs::create_dir_all(&self.root_directory).await;
+        let file_path = self.session_file_path(session_id);
+        let Ok(serialized) = serde_json::to_string(&message) else {
+            return;
+        };
+        if let Ok(mut file) = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path)
+            .await
+        {
+            let _ = file.write_all(serialized.as_bytes()).await;
+            let _ = file.write_all(b"\n").await;
+        }
+    }
+
+    async fn truncate(&self, session_id: &str, exchange_id: &str) {
+        let _guard = self.write_lock.lock().await;
+        let mut messages = self.read_all(session_id).await;
+        truncate_after_exchange(&mut messages, exchange_id);
+        self.write_all(session_id, &messages).await;
+    }
+}
+
+/// Keeps every message up to and including the last one stamped with
+/// `exchange_id`, dropping whatever was recorded afterwards.
+fn truncate_after_exchange(messages: &mut Vec<SessionChatMessage>, exchange_id: &str) {
+    if let Some(cut_at) = messages
+        .iter()
+        .rposition(|message| message.exchange_id() == Some(exchange_id))
+    {
+        messages.truncate(cut_at + 1);
+    }
+}
+
+pub type SharedChatSessionStore = Arc<dyn ChatSessionStore>;
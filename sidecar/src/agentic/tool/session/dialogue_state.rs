@@ -0,0 +1,178 @@
+//! Typed finite-state driver for the multi-turn session chat.
+//!
+//! `SessionChatClient` used to do a single stateless request/reply per
+//! `exchange_id`, leaning entirely on prompt text to keep the conversation on
+//! track. `ChatDialogueState` gives the driver a predictable, typed flow
+//! instead: the system prompt, temperature, and whether code edits are even
+//! allowed are all picked from the current state rather than guessed from the
+//! wording of the reply.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ChatDialogueState {
+    /// Gathering requirements; the assistant may ask a clarifying question
+    /// instead of acting.
+    Clarify,
+    /// Proposing an approach without touching any code yet.
+    Plan,
+    /// Making the agreed-upon code edits.
+    Edit,
+    /// Checking the edits just made against the original request.
+    Verify,
+    /// The conversation has resolved the request.
+    Done,
+}
+
+impl Default for ChatDialogueState {
+    fn default() -> Self {
+        ChatDialogueState::Clarify
+    }
+}
+
+impl ChatDialogueState {
+    /// Decides the next state from the state we were in plus the turn that
+    /// just happened. `Clarify` holds while the assistant's reply ends in a
+    /// question, `Plan` holds until the user confirms, `Edit` always moves on
+    /// to `Verify`, `Verify` goes back to `Edit` unless the user confirms,
+    /// and `Done` stays put.
+    pub fn next(self, assistant_reply: &str, user_reply: &str) -> ChatDialogueState {
+        match self {
+            ChatDialogueState::Clarify => {
+                if ends_with_question(assistant_reply) {
+                    ChatDialogueState::Clarify
+                } else {
+                    ChatDialogueState::Plan
+                }
+            }
+            ChatDialogueState::Plan => {
+                if user_confirms(user_reply) {
+                    ChatDialogueState::Edit
+                } else {
+                    ChatDialogueState::Plan
+                }
+            }
+            ChatDialogueState::Edit => ChatDialogueState::Verify,
+            ChatDialogueState::Verify => {
+                if user_confirms(user_reply) {
+                    ChatDialogueState::Done
+                } else {
+                    ChatDialogueState::Edit
+                }
+            }
+            ChatDialogueState::Done => ChatDialogueState::Done,
+        }
+    }
+
+    /// The extra system-prompt fragment for this state, appended after the
+    /// shared linking/formatting rules in `SessionChatClient::system_message`.
+    pub fn system_prompt_fragment(self) -> &'static str {
+        match self {
+            ChatDialogueState::Clarify => {
+                "- You are gathering requirements right now. If the request is ambiguous, ask a single focused clarifying question instead of acting. If it is already clear, say so and move on."
+            }
+            ChatDialogueState::Plan => {
+                "- You are proposing a plan right now. Describe the approach and which files you intend to touch, but do NOT make any code edits yet."
+            }
+            ChatDialogueState::Edit => {
+                "- You are making the agreed-upon code edits right now."
+            }
+            ChatDialogueState::Verify => {
+                "- You are checking the edits you just made against the original request. Point out anything that still needs changing before calling it done."
+            }
+            ChatDialogueState::Done => {
+                "- The request has been resolved. Only summarize what changed if asked."
+            }
+        }
+    }
+
+    pub fn temperature(self) -> f32 {
+        match self {
+            ChatDialogueState::Clarify => 0.3,
+            ChatDialogueState::Plan => 0.2,
+            ChatDialogueState::Edit => 0.2,
+            ChatDialogueState::Verify => 0.0,
+            ChatDialogueState::Done => 0.2,
+        }
+    }
+
+    pub fn allows_code_edits(self) -> bool {
+        matches!(self, ChatDialogueState::Edit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_edit_state_allows_code_edits() {
+        assert!(!ChatDialogueState::Clarify.allows_code_edits());
+        assert!(!ChatDialogueState::Plan.allows_code_edits());
+        assert!(ChatDialogueState::Edit.allows_code_edits());
+        assert!(!ChatDialogueState::Verify.allows_code_edits());
+        assert!(!ChatDialogueState::Done.allows_code_edits());
+    }
+
+    #[test]
+    fn clarify_holds_until_a_non_question_reply() {
+        assert_eq!(
+            ChatDialogueState::Clarify.next("which file did you mean?", "the parser"),
+            ChatDialogueState::Clarify
+        );
+        assert_eq!(
+            ChatDialogueState::Clarify.next("got it, here's the plan", "the parser"),
+            ChatDialogueState::Plan
+        );
+    }
+
+    #[test]
+    fn plan_only_advances_on_user_confirmation() {
+        assert_eq!(
+            ChatDialogueState::Plan.next("I'll touch foo.rs and bar.rs", "what about baz.rs?"),
+            ChatDialogueState::Plan
+        );
+        assert_eq!(
+            ChatDialogueState::Plan.next("I'll touch foo.rs and bar.rs", "lgtm"),
+            ChatDialogueState::Edit
+        );
+    }
+
+    #[test]
+    fn edit_always_advances_to_verify() {
+        assert_eq!(
+            ChatDialogueState::Edit.next("done", "thanks"),
+            ChatDialogueState::Verify
+        );
+    }
+
+    #[test]
+    fn verify_loops_back_to_edit_until_confirmed() {
+        assert_eq!(
+            ChatDialogueState::Verify.next("still missing the test", "please add tests"),
+            ChatDialogueState::Edit
+        );
+        assert_eq!(
+            ChatDialogueState::Verify.next("still missing the test", "looks good"),
+            ChatDialogueState::Done
+        );
+    }
+
+    #[test]
+    fn done_is_a_fixed_point() {
+        assert_eq!(
+            ChatDialogueState::Done.next("anything", "anything"),
+            ChatDialogueState::Done
+        );
+    }
+}
+
+fn ends_with_question(assistant_reply: &str) -> bool {
+    assistant_reply.trim_end().ends_with('?')
+}
+
+fn user_confirms(user_reply: &str) -> bool {
+    let normalized = user_reply.trim().to_lowercase();
+    matches!(
+        normalized.as_str(),
+        "yes" | "y" | "go ahead" | "looks good" | "lgtm" | "sounds good" | "do it"
+    )
+}
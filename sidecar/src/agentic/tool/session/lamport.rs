@@ -0,0 +1,125 @@
+//! Logical clock used to detect stale editor context.
+//!
+//! Without a logical clock there is no ordering guarantee between the `DiffRecentChanges`
+//! snapshot a `SessionChatClientRequest` was built from and whatever the
+//! editor does afterwards, so a reply can silently reference line numbers
+//! that moved out from under it - which matters given the strict `LX-LY`
+//! link formatting the system prompt demands. `Lamport` and
+//! `SessionLamportClock` give every recorded edit and every request a
+//! monotonically increasing stamp, the same causal-ordering primitive
+//! collaborative editor backends use to reason about concurrent buffer
+//! edits.
+
+use std::{collections::HashMap, sync::Arc};
+
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+pub struct Lamport {
+    pub counter: u64,
+    pub actor: Uuid,
+}
+
+impl Lamport {
+    pub fn new(counter: u64, actor: Uuid) -> Self {
+        Self { counter, actor }
+    }
+
+    pub fn zero(actor: Uuid) -> Self {
+        Self { counter: 0, actor }
+    }
+}
+
+/// Tracks the highest `Lamport` stamp observed per `session_id`.
+#[derive(Default)]
+pub struct SessionLamportClock {
+    latest: Mutex<HashMap<String, Lamport>>,
+}
+
+impl SessionLamportClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call this whenever an editor change is observed for `session_id`.
+    /// Bumps the session's counter to `max(local, observed) + 1` and returns
+    /// the stamp to attach to the recorded edit.
+    pub async fn observe_edit(&self, session_id: &str, observed: Lamport, actor: Uuid) -> Lamport {
+        let mut latest = self.latest.lock().await;
+        let next_counter = latest
+            .get(session_id)
+            .map(|stamp| stamp.counter)
+            .unwrap_or(0)
+            .max(observed.counter)
+            + 1;
+        let stamp = Lamport::new(next_counter, actor);
+        latest.insert(session_id.to_owned(), stamp);
+        stamp
+    }
+
+    /// The most recent stamp recorded for `session_id`, if any edits have
+    /// been observed yet.
+    pub async fn latest(&self, session_id: &str) -> Option<Lamport> {
+        self.latest.lock().await.get(session_id).copied()
+    }
+
+    /// True when `request_stamp` is behind the latest stamp recorded for the
+    /// session - i.e. the editor has moved on since the request was built
+    /// and any line-number links in a reply would risk being wrong.
+    pub async fn is_stale(&self, session_id: &str, request_stamp: Lamport) -> bool {
+        self.latest(session_id)
+            .await
+            .map(|latest| latest.counter > request_stamp.counter)
+            .unwrap_or(false)
+    }
+}
+
+pub type SharedSessionLamportClock = Arc<SessionLamportClock>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn observe_edit_advances_past_the_observed_counter() {
+        let clock = SessionLamportClock::new();
+        let actor = Uuid::new_v4();
+        let first = clock.observe_edit("session-a", Lamport::zero(actor), actor).await;
+        assert_eq!(first.counter, 1);
+        let second = clock.observe_edit("session-a", first, actor).await;
+        assert_eq!(second.counter, 2);
+    }
+
+    #[tokio::test]
+    async fn observe_edit_jumps_ahead_of_a_higher_observed_counter() {
+        let clock = SessionLamportClock::new();
+        let actor = Uuid::new_v4();
+        let remote_actor = Uuid::new_v4();
+        let observed_from_elsewhere = Lamport::new(41, remote_actor);
+        let stamp = clock
+            .observe_edit("session-a", observed_from_elsewhere, actor)
+            .await;
+        assert_eq!(stamp.counter, 42);
+    }
+
+    #[tokio::test]
+    async fn is_stale_only_once_a_later_edit_has_been_observed() {
+        let clock = SessionLamportClock::new();
+        let actor = Uuid::new_v4();
+        let request_stamp = clock.observe_edit("session-a", Lamport::zero(actor), actor).await;
+        assert!(!clock.is_stale("session-a", request_stamp).await);
+
+        clock.observe_edit("session-a", request_stamp, actor).await;
+        assert!(clock.is_stale("session-a", request_stamp).await);
+    }
+
+    #[tokio::test]
+    async fn sessions_are_tracked_independently() {
+        let clock = SessionLamportClock::new();
+        let actor = Uuid::new_v4();
+        let stamp_a = clock.observe_edit("session-a", Lamport::zero(actor), actor).await;
+        assert!(clock.latest("session-b").await.is_none());
+        assert!(!clock.is_stale("session-b", stamp_a).await);
+    }
+}